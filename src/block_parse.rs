@@ -0,0 +1,81 @@
+//! Parse noweb-style `<<name>>` chunk references out of extracted code
+//! text.
+
+use {Ilk, Span};
+
+const OPEN: &str = "<<";
+const CLOSE: &str = ">>";
+
+/// Split `code` into alternating runs of literal text (`Ilk::JustCode`)
+/// and chunk references (`Ilk::SectionName`), in source order. An
+/// unclosed `<<` yields a trailing `Ilk::Unterminated` span.
+pub fn parse(code: &str) -> Vec<Span> {
+    let mut spans = Vec::new();
+    let mut pos = 0;
+
+    while let Some(found) = code[pos..].find(OPEN) {
+        let start = pos + found;
+        if start > pos {
+            spans.push(Span {
+                lo: pos,
+                hi: start,
+                ilk: Ilk::JustCode,
+            });
+        }
+
+        match code[start + OPEN.len()..].find(CLOSE) {
+            Some(len) => {
+                let hi = start + OPEN.len() + len + CLOSE.len();
+                spans.push(Span {
+                    lo: start,
+                    hi,
+                    ilk: Ilk::SectionName,
+                });
+                pos = hi;
+            }
+            None => {
+                spans.push(Span {
+                    lo: start,
+                    hi: code.len(),
+                    ilk: Ilk::Unterminated("<<"),
+                });
+                pos = code.len();
+            }
+        }
+    }
+
+    if pos < code.len() {
+        spans.push(Span {
+            lo: pos,
+            hi: code.len(),
+            ilk: Ilk::JustCode,
+        });
+    }
+
+    spans
+}
+
+/// Pull the bare chunk name out of a `Ilk::SectionName` span's text, e.g.
+/// `"<<frobnicate>>"` -> `"frobnicate"`.
+pub fn section_name(text: &str) -> &str {
+    text.trim_start_matches(OPEN).trim_end_matches(CLOSE).trim()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use Ilk;
+
+    #[test]
+    fn splits_code_and_references() {
+        let spans = parse("let x = <<value>>;");
+        let ilks: Vec<_> = spans.iter().map(|s| s.ilk.clone()).collect();
+        assert_eq!(ilks, vec![Ilk::JustCode, Ilk::SectionName, Ilk::JustCode]);
+    }
+
+    #[test]
+    fn flags_unterminated_reference() {
+        let spans = parse("let x = <<value;");
+        assert_eq!(spans.last().unwrap().ilk, Ilk::Unterminated("<<"));
+    }
+}
@@ -0,0 +1,167 @@
+//! Locate fenced code blocks in a Markdown document, and parse their
+//! fence info strings the way rustdoc parses doctest attributes.
+
+use pulldown_cmark::{Event, Parser, Tag};
+
+use Ilk;
+
+/// A single fenced code block: its parsed info string and the byte range
+/// of its contents within the original Markdown source.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeBlock {
+    pub lang: LangString,
+    pub lo: usize,
+    pub hi: usize,
+}
+
+/// The flags carried by a fence's info string, e.g. ` ```rust,no_run` `.
+/// Modeled on rustdoc's doctest `LangString`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LangString {
+    /// The language token, e.g. `"rust"`. Empty if the fence gave none.
+    pub lang: String,
+    /// Don't tangle or test this block. Weave still renders it like any
+    /// other block.
+    pub ignore: bool,
+    /// Compile but don't execute this block.
+    pub no_run: bool,
+    /// This block must panic at runtime to be considered passing.
+    pub should_panic: bool,
+    /// This block must fail to compile to be considered passing.
+    pub compile_fail: bool,
+    /// The compiler edition to build this block under, if given.
+    pub edition: Option<u16>,
+}
+
+impl LangString {
+    fn new() -> LangString {
+        LangString {
+            lang: String::new(),
+            ignore: false,
+            no_run: false,
+            should_panic: false,
+            compile_fail: false,
+            edition: None,
+        }
+    }
+
+    /// Whether this block should be concatenated into the tangled output
+    /// for the given tangle language token (normally `"rust"`).
+    pub fn should_tangle(&self, tangle_lang: &str) -> bool {
+        !self.ignore && self.lang == tangle_lang
+    }
+}
+
+/// Parse a fence info string, e.g. `"rust,no_run,edition=2018"`, into its
+/// language and flags. Returns `Ilk::NotFound` (carrying an explanatory
+/// message) if an `edition=` value isn't a number, or if a token other
+/// than the first is neither a known flag nor `edition=`.
+pub fn parse_lang_string(info: &str) -> Result<LangString, Ilk> {
+    let mut result = LangString::new();
+
+    for token in info.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+        match token {
+            "ignore" => result.ignore = true,
+            "no_run" => result.no_run = true,
+            "should_panic" => result.should_panic = true,
+            "compile_fail" => result.compile_fail = true,
+            _ => if let Some(value) = token.strip_prefix_compat("edition=") {
+                result.edition = Some(value.parse().map_err(|_| {
+                    Ilk::NotFound(format!("invalid edition attribute `{}`", value))
+                })?);
+            } else if result.lang.is_empty() {
+                result.lang = token.to_string();
+            } else {
+                return Err(Ilk::NotFound(format!("unknown code block attribute `{}`", token)));
+            },
+        }
+    }
+
+    Ok(result)
+}
+
+// `str::strip_prefix` isn't stable on every toolchain this crate
+// supports; this is a minimal stand-in scoped to this module's needs.
+trait StripPrefixCompat {
+    fn strip_prefix_compat<'a>(&'a self, prefix: &str) -> Option<&'a str>;
+}
+
+impl StripPrefixCompat for str {
+    fn strip_prefix_compat<'a>(&'a self, prefix: &str) -> Option<&'a str> {
+        if self.starts_with(prefix) {
+            Some(&self[prefix.len()..])
+        } else {
+            None
+        }
+    }
+}
+
+/// Walk `source` and collect every fenced code block, in document order.
+///
+/// The `Start`/`End` `CodeBlock` events from `pulldown_cmark` span the
+/// whole fenced construct, fence delimiters included, so the block's
+/// `lo`/`hi` are taken from the nested `Text` event(s) that carry the
+/// body instead.
+pub fn extract(source: &str) -> Result<Vec<CodeBlock>, Ilk> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(String, Option<(usize, usize)>)> = None;
+
+    for (event, range) in Parser::new(source).into_offset_iter() {
+        match event {
+            // `Tag::CodeBlock` wraps a bare `CowStr` (with `.into_string()`)
+            // in pulldown-cmark 0.5, pinned in Cargo.toml; the later
+            // `CodeBlockKind` wrapper that needs unwrapping first doesn't
+            // land until 0.6.
+            Event::Start(Tag::CodeBlock(info)) => {
+                current = Some((info.into_string(), None));
+            }
+            Event::Text(_) => {
+                if let Some((_, ref mut body)) = current {
+                    *body = Some(match *body {
+                        Some((lo, _)) => (lo, range.end),
+                        None => (range.start, range.end),
+                    });
+                }
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                if let Some((info, body)) = current.take() {
+                    let (lo, hi) = body.unwrap_or((range.end, range.end));
+                    blocks.push(CodeBlock {
+                        lang: parse_lang_string(&info)?,
+                        lo,
+                        hi,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    Ok(blocks)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{extract, parse_lang_string};
+
+    #[test]
+    fn parses_flags() {
+        let lang = parse_lang_string("rust,no_run,edition=2018").unwrap();
+        assert_eq!(lang.lang, "rust");
+        assert!(lang.no_run);
+        assert_eq!(lang.edition, Some(2018));
+    }
+
+    #[test]
+    fn rejects_non_numeric_edition() {
+        assert!(parse_lang_string("rust,edition=yesterday").is_err());
+    }
+
+    #[test]
+    fn extracted_range_excludes_fence_delimiters() {
+        let markdown = "```rust\nfn main() {}\n```\n";
+        let blocks = extract(markdown).unwrap();
+        assert_eq!(blocks.len(), 1);
+        assert_eq!(&markdown[blocks[0].lo..blocks[0].hi], "fn main() {}\n");
+    }
+}
@@ -0,0 +1,24 @@
+//! Identify an input file for diagnostics and source maps, mirroring
+//! rustdoc's `FileName`.
+
+use std::fmt;
+use std::path::PathBuf;
+
+/// A logical name for a chunk of literate Markdown source. `Real`
+/// identifies an actual file on disk; `Virtual` names something with no
+/// path of its own (e.g. piped stdin), so multiple inputs can still be
+/// told apart in diagnostics.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FileName {
+    Real(PathBuf),
+    Virtual(String),
+}
+
+impl fmt::Display for FileName {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FileName::Real(ref path) => write!(f, "{}", path.display()),
+            FileName::Virtual(ref name) => write!(f, "<{}>", name),
+        }
+    }
+}
@@ -1,18 +1,18 @@
-// #![cfg_attr(feature = "cargo-clippy", deny(clippy, clippy_pedantic))]
+// #![cfg_attr(clippy, deny(clippy::all, clippy::pedantic))]
 #![allow(unused)]
-#![cfg_attr(feature = "cargo-clippy", deny(clippy))]
-#![cfg_attr(feature = "cargo-clippy", warn(clippy_pedantic))]
-#![cfg_attr(feature = "cargo-clippy",
+#![cfg_attr(clippy, deny(clippy::all))]
+#![cfg_attr(clippy, warn(clippy::pedantic))]
+#![cfg_attr(clippy,
     allow(
-        redundant_field_names, // Bug in clippy v0.0.187?
-        missing_docs_in_private_items, // For now, the Markdown source contains the private docs
-        print_stdout,
+        clippy::redundant_field_names, // Bug in clippy v0.0.187?
+        clippy::missing_docs_in_private_items, // For now, the Markdown source contains the private docs
+        clippy::print_stdout,
         // for readability
-        non_ascii_literal,
-        option_unwrap_used,
-        result_unwrap_used,
-        shadow_same,
-        string_add,
+        clippy::non_ascii_literal,
+        clippy::option_unwrap_used,
+        clippy::result_unwrap_used,
+        clippy::shadow_same,
+        clippy::string_add,
     ))]
 //! See README
 
@@ -21,6 +21,7 @@ use std::fmt;
 extern crate memchr;
 extern crate pulldown_cmark;
 extern crate regex;
+extern crate tempfile;
 
 #[macro_use]
 extern crate lazy_static;
@@ -28,7 +29,10 @@ extern crate lazy_static;
 #[macro_use]
 extern crate failure;
 
+pub mod file_name;
 pub mod tangle;
+pub mod test_runner;
+pub mod weave;
 mod block_parse;
 mod code_extractor;
 mod line_counter;
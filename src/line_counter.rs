@@ -0,0 +1,38 @@
+//! Map byte offsets in a source string back to 1-based line numbers.
+
+/// A precomputed table of the byte offset where each line of a source
+/// string begins, so repeated offset-to-line lookups don't have to
+/// rescan from the start.
+pub struct LineCounter {
+    line_starts: Vec<usize>,
+}
+
+impl LineCounter {
+    /// Build a counter for `source`.
+    pub fn new(source: &str) -> LineCounter {
+        let mut line_starts = vec![0];
+        line_starts.extend(source.match_indices('\n').map(|(i, _)| i + 1));
+        LineCounter { line_starts }
+    }
+
+    /// Return the 1-based line number containing byte offset `pos`.
+    pub fn line_at(&self, pos: usize) -> usize {
+        match self.line_starts.binary_search(&pos) {
+            Ok(i) => i + 1,
+            Err(i) => i,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LineCounter;
+
+    #[test]
+    fn finds_lines() {
+        let counter = LineCounter::new("one\ntwo\nthree\n");
+        assert_eq!(counter.line_at(0), 1);
+        assert_eq!(counter.line_at(4), 2);
+        assert_eq!(counter.line_at(8), 3);
+    }
+}
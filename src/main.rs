@@ -7,21 +7,58 @@ use std::io::Read;
 use std::path::PathBuf;
 
 extern crate draft;
-use draft::tangle::Tangle;
+use draft::file_name::FileName;
+use draft::tangle::{SourceMapEntry, Tangle};
+use draft::test_runner::{self, Outcome};
+use draft::weave::{self, WeaveOptions};
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "draft")]
 /// Extract Rust from Markdown files
 struct Opt {
     /// Markdown input file(s)
-    #[structopt(parse(from_os_str), required)]
+    #[structopt(parse(from_os_str), raw(required = "true"))]
     inputs: Vec<PathBuf>,
+
+    /// Render to a standalone HTML page instead of tangling
+    #[structopt(long = "html")]
+    html: bool,
+
+    /// CSS file to link from the rendered HTML (repeatable)
+    #[structopt(long = "markdown-css", parse(from_os_str))]
+    markdown_css: Vec<PathBuf>,
+
+    /// File whose contents are spliced into <head> (repeatable)
+    #[structopt(long = "markdown-in-header", parse(from_os_str))]
+    markdown_in_header: Vec<PathBuf>,
+
+    /// File whose contents are spliced just before the rendered content (repeatable)
+    #[structopt(long = "markdown-before-content", parse(from_os_str))]
+    markdown_before_content: Vec<PathBuf>,
+
+    /// File whose contents are spliced just after the rendered content (repeatable)
+    #[structopt(long = "markdown-after-content", parse(from_os_str))]
+    markdown_after_content: Vec<PathBuf>,
+
+    /// Precede each tangled block with a `// draft-src: path:line` comment
+    #[structopt(long = "provenance-comments")]
+    provenance_comments: bool,
+
+    /// Write a JSON source map from tangled lines back to the Markdown to PATH.
+    /// Not written when the inputs define root-file sections; when given,
+    /// --provenance-comments has no effect
+    #[structopt(long = "sourcemap", parse(from_os_str))]
+    sourcemap: Option<PathBuf>,
+
+    /// Compile (and run) the Rust blocks instead of tangling
+    #[structopt(long = "test")]
+    test: bool,
 }
 
 use std::process;
 fn main() {
     match run() {
-        Ok(_) => (),
+        Ok(code) => process::exit(code),
         Err(e) => {
             eprintln!("{}", e);
             process::exit(1);
@@ -32,20 +69,121 @@ fn main() {
 extern crate failure;
 use failure::Error;
 
-fn run() -> Result<(), Error> {
+fn run() -> Result<i32, Error> {
     let opts = Opt::from_args();
-    let path = &opts.inputs[0];
-    let markdown = slurp(path)?;
 
-    let tangle = Tangle::new(&markdown);
-    print!("{}", tangle.get("")?);
-    Ok(())
+    if opts.html {
+        let markdown = slurp(&opts.inputs[0])?;
+        let weave_opts = WeaveOptions {
+            css: opts.markdown_css,
+            in_header: opts.markdown_in_header,
+            before_content: opts.markdown_before_content,
+            after_content: opts.markdown_after_content,
+        };
+        print!("{}", weave::weave(&markdown, &weave_opts)?);
+        return Ok(0);
+    }
+
+    if opts.test {
+        return run_tests(&opts.inputs);
+    }
+
+    let mut inputs = opts.inputs.iter();
+    let first = inputs.next().expect("structopt requires at least one input");
+    let mut tangle = Tangle::with_tangle_lang(FileName::Real(first.clone()), &slurp(first)?, "rust")?;
+    for path in inputs {
+        tangle.absorb(FileName::Real(path.clone()), &slurp(path)?, "rust")?;
+    }
+
+    let files = tangle.files(opts.provenance_comments)?;
+    if !files.is_empty() {
+        for (relative_path, contents) in files {
+            if let Some(parent) = relative_path.parent() {
+                if !parent.as_os_str().is_empty() {
+                    std::fs::create_dir_all(parent)?;
+                }
+            }
+            use std::io::Write;
+            File::create(&relative_path)?.write_all(contents.as_bytes())?;
+        }
+        return Ok(0);
+    }
+
+    if let Some(ref sourcemap_path) = opts.sourcemap {
+        let (rust, entries) = tangle.sourcemap("")?;
+        write_sourcemap(sourcemap_path, &entries)?;
+        print!("{}", rust);
+    } else if opts.provenance_comments {
+        print!("{}", tangle.get_annotated("")?);
+    } else {
+        print!("{}", tangle.get("")?);
+    }
+    Ok(0)
 }
 
 use std::fs::File;
 
+/// Run every input's tests and print a summary, returning the process
+/// exit code (`0` if every block passed, `1` otherwise) for `main` to
+/// act on rather than exiting here directly.
+fn run_tests(inputs: &[PathBuf]) -> Result<i32, Error> {
+    let mut summary = test_runner::Summary::default();
+    for path in inputs {
+        let markdown = slurp(path)?;
+        let file_summary = test_runner::run(&markdown)?;
+        for result in &file_summary.results {
+            if let Outcome::Failed(ref message) = result.outcome {
+                eprintln!("{}:{}: {}", path.display(), result.src_line, message);
+            }
+        }
+        summary.results.extend(file_summary.results);
+    }
+
+    println!("{}", summary);
+    Ok(if summary.all_passed() { 0 } else { 1 })
+}
+
 fn slurp(path: &PathBuf) -> Result<String, Error> {
     let mut result = String::new();
     File::open(path)?.read_to_string(&mut result)?;
     Ok(result)
 }
+
+fn write_sourcemap(path: &PathBuf, entries: &[SourceMapEntry]) -> Result<(), Error> {
+    use std::io::Write;
+
+    let mut json = String::from("[\n");
+    for (i, entry) in entries.iter().enumerate() {
+        if i > 0 {
+            json.push_str(",\n");
+        }
+        json.push_str(&format!(
+            "  {{\"gen_line\": {}, \"src_path\": {}, \"src_line\": {}}}",
+            entry.gen_line,
+            json_string(&entry.src_path),
+            entry.src_line
+        ));
+    }
+    json.push_str("\n]\n");
+
+    File::create(path)?.write_all(json.as_bytes())?;
+    Ok(())
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
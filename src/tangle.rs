@@ -0,0 +1,449 @@
+//! Concatenate a literate Markdown document's named code blocks
+//! ("tangle" them, in literate-programming parlance) into Rust source,
+//! optionally annotated with where each block came from.
+
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use failure::Error;
+
+use block_parse;
+use code_extractor;
+use file_name::FileName;
+use line_counter::LineCounter;
+use {Ilk, Span};
+
+/// One contiguous run of code backing a section, tagged with the file
+/// and byte range it came from so tangled output can be mapped back to
+/// its Markdown origin.
+struct Piece {
+    src: Rc<FileName>,
+    markdown: Rc<str>,
+    lo: usize,
+    hi: usize,
+}
+
+/// The tangled form of one or more literate Markdown documents: every
+/// fenced code block in the tangle language that isn't marked `ignore`,
+/// grouped by the section name it defines. The
+/// unnamed root section is keyed by `""`.
+pub struct Tangle {
+    sections: HashMap<String, Vec<Piece>>,
+}
+
+/// One row of a `--sourcemap` file: which originating file and line a
+/// given line of tangled output came from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceMapEntry {
+    pub gen_line: usize,
+    pub src_path: String,
+    pub src_line: usize,
+}
+
+impl Tangle {
+    /// Extract and group every Rust code block in `markdown`, skipping
+    /// any block marked `ignore`.
+    pub fn new(markdown: &str) -> Result<Tangle, Error> {
+        Tangle::with_tangle_lang(FileName::Virtual("input".to_string()), markdown, "rust")
+    }
+
+    /// Like `new`, but tag every block as coming from `src`, and only
+    /// tangle blocks whose language token is `tangle_lang`.
+    pub fn with_tangle_lang(src: FileName, markdown: &str, tangle_lang: &str) -> Result<Tangle, Error> {
+        let mut tangle = Tangle {
+            sections: HashMap::new(),
+        };
+        tangle.absorb(src, markdown, tangle_lang)?;
+        Ok(tangle)
+    }
+
+    /// Extract every code block in `markdown` whose language token is
+    /// `tangle_lang` and that isn't marked `ignore`, and merge them into
+    /// this tangle's sections, tagged as coming from `src`. Used to
+    /// build a single namespace out of several input files.
+    pub fn absorb(&mut self, src: FileName, markdown: &str, tangle_lang: &str) -> Result<(), Error> {
+        let src = Rc::new(src);
+        let markdown: Rc<str> = Rc::from(markdown);
+
+        for block in code_extractor::extract(&markdown).map_err(|ilk| format_err!("{}", ilk))? {
+            if !block.lang.should_tangle(tangle_lang) {
+                continue;
+            }
+            let (name, lo, hi) = split_header(&markdown, block.lo, block.hi);
+            self.sections
+                .entry(name.to_string())
+                .or_insert_with(Vec::new)
+                .push(Piece {
+                    src: Rc::clone(&src),
+                    markdown: Rc::clone(&markdown),
+                    lo,
+                    hi,
+                });
+        }
+
+        Ok(())
+    }
+
+    /// Render the named section, recursively expanding any `<<name>>`
+    /// references it contains. Pass `""` for the unnamed root section.
+    pub fn get(&self, name: &str) -> Result<String, Error> {
+        let mut out = String::new();
+        self.expand(name, false, &mut out, &mut Vec::new(), &mut 1, &mut Vec::new())?;
+        Ok(out)
+    }
+
+    /// Like `get`, but precede each block with a `// draft-src:
+    /// path:line` comment naming the Markdown it was extracted from.
+    pub fn get_annotated(&self, name: &str) -> Result<String, Error> {
+        let mut out = String::new();
+        self.expand(name, true, &mut out, &mut Vec::new(), &mut 1, &mut Vec::new())?;
+        Ok(out)
+    }
+
+    /// Render the named section and, alongside it, a source map from the
+    /// generated output back to the Markdown it came from. There is one
+    /// entry wherever a run of lines from a single origin begins; a line
+    /// between entries maps to the preceding entry's `src_line` plus its
+    /// distance from that entry's `gen_line`.
+    pub fn sourcemap(&self, name: &str) -> Result<(String, Vec<SourceMapEntry>), Error> {
+        let mut out = String::new();
+        let mut entries = Vec::new();
+        self.expand(name, false, &mut out, &mut entries, &mut 1, &mut Vec::new())?;
+        Ok((out, entries))
+    }
+
+    /// Expand one already-located span of code the same way a registered
+    /// section's piece would expand — substituting any `<<name>>`
+    /// references it contains — and return the generated text alongside
+    /// its per-splice provenance. Used by the `--test` runner to compile
+    /// what a block (which may itself be a section definition, and/or
+    /// reference others) actually tangles to, while still being able to
+    /// translate rustc's line numbers back to this Markdown.
+    pub(crate) fn expand_block(&self, markdown: &str, lo: usize, hi: usize) -> Result<(String, Vec<SourceMapEntry>), Error> {
+        let src = FileName::Virtual("test block".to_string());
+        let mut out = String::new();
+        let mut entries = Vec::new();
+        let mut gen_line = 1;
+        self.expand_piece(&src, markdown, lo, hi, false, &mut out, &mut entries, &mut gen_line, &mut Vec::new())?;
+        Ok((out, entries))
+    }
+
+    /// The names of every section this tangle defines, excluding the
+    /// unnamed root.
+    pub fn section_names(&self) -> Vec<&str> {
+        self.sections
+            .keys()
+            .map(String::as_str)
+            .filter(|name| !name.is_empty())
+            .collect()
+    }
+
+    /// Every *root-file* section — one whose name looks like a relative
+    /// file path, e.g. `<<src/main.rs>>=` — rendered and paired with the
+    /// path it should be written to. A document with no such sections
+    /// yields an empty list; callers fall back to the unnamed root.
+    pub fn files(&self, annotate: bool) -> Result<Vec<(PathBuf, String)>, Error> {
+        let mut out = Vec::new();
+        for name in self.section_names() {
+            if looks_like_path(name) {
+                let rendered = if annotate {
+                    self.get_annotated(name)?
+                } else {
+                    self.get(name)?
+                };
+                out.push((PathBuf::from(name), rendered));
+            }
+        }
+        Ok(out)
+    }
+
+    /// `visiting` is the stack of section names currently being expanded,
+    /// innermost last; a name already on it means `name` (directly or
+    /// transitively) references itself, which would otherwise recurse
+    /// forever instead of erroring.
+    fn expand(
+        &self,
+        name: &str,
+        annotate: bool,
+        out: &mut String,
+        entries: &mut Vec<SourceMapEntry>,
+        gen_line: &mut usize,
+        visiting: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        if visiting.iter().any(|seen| seen == name) {
+            bail!(
+                "{}",
+                Ilk::NotFound(format!("circular reference: section `{}` references itself", name))
+            );
+        }
+
+        let pieces = self.sections.get(name).ok_or_else(|| {
+            format_err!("{}", Ilk::NotFound(format!("section not found: `{}`", name)))
+        })?;
+
+        visiting.push(name.to_string());
+
+        for piece in pieces {
+            self.expand_piece(
+                &piece.src,
+                &piece.markdown,
+                piece.lo,
+                piece.hi,
+                annotate,
+                out,
+                entries,
+                gen_line,
+                visiting,
+            )?;
+        }
+
+        visiting.pop();
+        Ok(())
+    }
+
+    /// Emit one span of code — a registered piece, or (via
+    /// `expand_block`) an ad hoc one — plus its provenance entry, then
+    /// recurse into `expand` for any `<<name>>` reference it contains.
+    #[cfg_attr(clippy, allow(clippy::too_many_arguments))]
+    fn expand_piece(
+        &self,
+        src: &FileName,
+        markdown: &str,
+        lo: usize,
+        hi: usize,
+        annotate: bool,
+        out: &mut String,
+        entries: &mut Vec<SourceMapEntry>,
+        gen_line: &mut usize,
+        visiting: &mut Vec<String>,
+    ) -> Result<(), Error> {
+        let counter = LineCounter::new(markdown);
+        let src_line = counter.line_at(lo);
+        if annotate {
+            let comment = format!("// draft-src: {}:{}\n", src, src_line);
+            *gen_line += comment.matches('\n').count();
+            out.push_str(&comment);
+        }
+        entries.push(SourceMapEntry {
+            gen_line: *gen_line,
+            src_path: src.to_string(),
+            src_line,
+        });
+
+        let body = &markdown[lo..hi];
+        for span in block_parse::parse(body) {
+            match span.ilk {
+                Ilk::JustCode => {
+                    let chunk = &body[span.lo..span.hi];
+                    *gen_line += chunk.matches('\n').count();
+                    out.push_str(chunk);
+                }
+                Ilk::SectionName => {
+                    let ref_name = block_parse::section_name(&body[span.lo..span.hi]);
+                    self.expand(ref_name, annotate, out, entries, gen_line, visiting)?;
+                    // What follows the reference is this piece's own code
+                    // again, so it gets an entry of its own.
+                    entries.push(SourceMapEntry {
+                        gen_line: *gen_line,
+                        src_path: src.to_string(),
+                        src_line: counter.line_at(lo + span.hi),
+                    });
+                }
+                Ilk::Unterminated(thingy) => bail!("{}", Ilk::Unterminated(thingy)),
+                Ilk::NotFound(_) => unreachable!("not produced by block_parse::parse"),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a section name should be treated as a root-file directive
+/// rather than just an internal `<<name>>` chunk. A dotted chunk name
+/// like `<<main.loop>>` is ordinary noweb convention, so a bare `.` or
+/// `/` isn't enough: the name must contain a `/` *and* end in `.rs`,
+/// and mustn't climb out of the output directory via `..` or an
+/// absolute path.
+fn looks_like_path(name: &str) -> bool {
+    if !name.contains('/') {
+        return false;
+    }
+
+    let path = Path::new(name);
+    if path.is_absolute() {
+        return false;
+    }
+    if path.components().any(|c| c == Component::ParentDir) {
+        return false;
+    }
+
+    path.extension().and_then(|ext| ext.to_str()) == Some("rs")
+}
+
+/// The section name declared by a `<<name>>=` header, if `text` (a code
+/// block's body, or the start of one) begins with one.
+pub(crate) fn header_name(text: &str) -> Option<&str> {
+    let trimmed = text.trim_start();
+    if !trimmed.starts_with("<<") {
+        return None;
+    }
+    let close = trimmed.find(">>=")?;
+    Some(trimmed[2..close].trim())
+}
+
+/// Narrow a fenced code block's byte range past any `<<name>>=` header,
+/// returning the section name (empty for the unnamed root) and the
+/// `(lo, hi)` range of the code that follows it. Shared with `weave` and
+/// the `--test` runner, which both need to tell a block's header apart
+/// from the code it introduces.
+pub(crate) fn split_header(markdown: &str, lo: usize, hi: usize) -> (&str, usize, usize) {
+    let text = &markdown[lo..hi];
+    let trimmed = text.trim_start();
+    let skipped = text.len() - trimmed.len();
+
+    if let Some(name) = header_name(trimmed) {
+        let close = trimmed.find(">>=").expect("header_name found one");
+        let header_end = lo + skipped + close + 3;
+        let rest = &markdown[header_end..hi];
+        let rest_lo = header_end + (rest.len() - rest.trim_start_matches('\n').len());
+        return (name, rest_lo, hi);
+    }
+
+    ("", lo, hi)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Tangle;
+
+    #[test]
+    fn expand_follows_section_references() {
+        let markdown = "\
+```rust
+<<src/main.rs>>=
+fn main() {
+<<body>>
+}
+```
+
+```rust
+<<body>>=
+println!(\"hi\");
+```
+";
+        let tangle = Tangle::new(markdown).unwrap();
+        assert_eq!(
+            tangle.get("src/main.rs").unwrap(),
+            "fn main() {\nprintln!(\"hi\");\n\n}\n"
+        );
+    }
+
+    #[test]
+    fn expand_reports_missing_section_with_context() {
+        let markdown = "\
+```rust
+<<src/main.rs>>=
+<<nonexistent>>
+```
+";
+        let tangle = Tangle::new(markdown).unwrap();
+        let err = tangle.get("src/main.rs").unwrap_err();
+        assert_eq!(err.to_string(), "section not found: `nonexistent`");
+    }
+
+    #[test]
+    fn sourcemap_pairs_gen_line_with_src_line() {
+        let markdown = "\
+```rust
+<<src/main.rs>>=
+fn main() {
+<<body>>
+}
+```
+
+```rust
+<<body>>=
+println!(\"hi\");
+```
+";
+        let tangle = Tangle::new(markdown).unwrap();
+        let (rust, entries) = tangle.sourcemap("src/main.rs").unwrap();
+        assert_eq!(rust, "fn main() {\nprintln!(\"hi\");\n\n}\n");
+        // The root piece starts at generated line 1, and the referenced
+        // `body` piece is spliced in starting at generated line 2, where
+        // `<<body>>` appeared.
+        assert_eq!(entries[0].gen_line, 1);
+        assert_eq!(entries[1].gen_line, 2);
+    }
+
+    #[test]
+    fn expand_block_maps_a_spliced_reference_back_to_its_own_line() {
+        let markdown = "\
+```rust
+fn main() {
+<<body>>
+}
+```
+
+```rust
+<<body>>=
+println!(\"hi\");
+```
+";
+        let tangle = Tangle::new(markdown).unwrap();
+        let block = "fn main() {\n<<body>>\n}\n";
+        let lo = markdown.find(block).unwrap();
+        let (code, entries) = tangle.expand_block(markdown, lo, lo + block.len()).unwrap();
+        assert_eq!(code, "fn main() {\nprintln!(\"hi\");\n\n}\n");
+        // gen_line 1 is the block's own first line; gen_line 2 is where
+        // `<<body>>` was spliced in, pointing at `body`'s own line.
+        assert_eq!(entries[0].gen_line, 1);
+        assert_eq!(entries[1].gen_line, 2);
+        assert_ne!(entries[0].src_line, entries[1].src_line);
+    }
+
+    #[test]
+    fn looks_like_path_requires_slash_and_rs_extension() {
+        assert!(super::looks_like_path("src/main.rs"));
+        assert!(!super::looks_like_path("main.rs"));
+        assert!(!super::looks_like_path("main.loop"));
+        assert!(!super::looks_like_path("src/main.txt"));
+    }
+
+    #[test]
+    fn expand_rejects_self_reference_instead_of_overflowing() {
+        let markdown = "\
+```rust
+<<loop>>=
+<<loop>>
+```
+";
+        let tangle = Tangle::new(markdown).unwrap();
+        let err = tangle.get("loop").unwrap_err();
+        assert_eq!(err.to_string(), "circular reference: section `loop` references itself");
+    }
+
+    #[test]
+    fn expand_rejects_mutual_reference() {
+        let markdown = "\
+```rust
+<<a>>=
+<<b>>
+```
+
+```rust
+<<b>>=
+<<a>>
+```
+";
+        let tangle = Tangle::new(markdown).unwrap();
+        let err = tangle.get("a").unwrap_err();
+        assert_eq!(err.to_string(), "circular reference: section `a` references itself");
+    }
+
+    #[test]
+    fn looks_like_path_rejects_traversal() {
+        assert!(!super::looks_like_path("../../.ssh/authorized_keys.rs"));
+        assert!(!super::looks_like_path("/etc/passwd.rs"));
+    }
+}
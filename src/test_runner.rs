@@ -0,0 +1,238 @@
+//! Compile, and optionally run, the Rust blocks in a literate Markdown
+//! document — analogous to rustdoc's doctest runner.
+
+use std::fmt;
+use std::fs;
+use std::process::Command;
+
+use failure::Error;
+use regex::Regex;
+use tempfile::Builder;
+
+use code_extractor::{self, LangString};
+use line_counter::LineCounter;
+use tangle::{self, SourceMapEntry, Tangle};
+
+lazy_static! {
+    // Matches the line number rustc embeds in a diagnostic that points
+    // into our generated `main.rs`, e.g. "main.rs:3:5" or "--> main.rs:3".
+    static ref MAIN_RS_LINE: Regex = Regex::new(r"main\.rs:(\d+)").unwrap();
+    // A real `fn main(` definition, not just any identifier that happens
+    // to contain "main" (e.g. `fn main_helper(`).
+    static ref FN_MAIN: Regex = Regex::new(r"\bfn\s+main\s*\(").unwrap();
+}
+
+/// The result of compiling (and maybe running) a single block.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+/// One block's outcome, tagged with the Markdown line it came from so
+/// failures are human-navigable.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResult {
+    pub src_line: usize,
+    pub outcome: Outcome,
+}
+
+/// The results of a `--test` run over a document.
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub results: Vec<TestResult>,
+}
+
+impl Summary {
+    pub fn passed(&self) -> usize {
+        self.results
+            .iter()
+            .filter(|r| r.outcome == Outcome::Passed)
+            .count()
+    }
+
+    pub fn failed(&self) -> usize {
+        self.results.len() - self.passed()
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.failed() == 0
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} passed; {} failed", self.passed(), self.failed())
+    }
+}
+
+/// Compile, and unless `no_run` is set execute, every non-`ignore` Rust
+/// block in `markdown`.
+pub fn run(markdown: &str) -> Result<Summary, Error> {
+    let counter = LineCounter::new(markdown);
+    let tangle = Tangle::new(markdown)?;
+    let mut results = Vec::new();
+
+    for block in code_extractor::extract(markdown).map_err(|ilk| format_err!("{}", ilk))? {
+        if block.lang.ignore || block.lang.lang != "rust" {
+            continue;
+        }
+        let src_line = counter.line_at(block.lo);
+        // A block may itself be a `<<name>>=` section definition, and/or
+        // contain `<<ref>>` chunk references; rustc needs neither the
+        // noweb header nor the raw placeholders, it needs the tangled
+        // Rust they stand for. `expand_block` hands back that rendering
+        // alongside a sourcemap entry per splice, so a reference pulling
+        // in another section's lines doesn't throw off where rustc's
+        // diagnostics are translated back to.
+        let (_, code_lo, code_hi) = tangle::split_header(markdown, block.lo, block.hi);
+        let (code, entries) = tangle.expand_block(markdown, code_lo, code_hi)?;
+        results.push(TestResult {
+            src_line,
+            outcome: run_one(&code, &block.lang, &entries)?,
+        });
+    }
+
+    Ok(Summary { results })
+}
+
+/// Compile and run one block in its own temp directory, honoring its
+/// `compile_fail`/`no_run`/`should_panic`/`edition` flags. `entries` is
+/// the block's provenance, one entry per spliced-in piece, used to
+/// translate rustc's `main.rs:N` diagnostics back to where the author
+/// can see them.
+fn run_one(code: &str, lang: &LangString, entries: &[SourceMapEntry]) -> Result<Outcome, Error> {
+    let wrapped = !FN_MAIN.is_match(code);
+
+    let dir = Builder::new().prefix("draft-test").tempdir()?;
+    let src_path = dir.path().join("main.rs");
+    fs::write(&src_path, wrap(code, wrapped))?;
+
+    let exe_path = dir.path().join("main");
+    let mut rustc = Command::new("rustc");
+    rustc.arg(&src_path).arg("-o").arg(&exe_path);
+    if let Some(edition) = lang.edition {
+        rustc.arg("--edition").arg(edition.to_string());
+    }
+    let compiled = rustc.output()?;
+
+    if lang.compile_fail {
+        return Ok(if compiled.status.success() {
+            Outcome::Failed("expected compile_fail, but it compiled".to_string())
+        } else {
+            Outcome::Passed
+        });
+    }
+    if !compiled.status.success() {
+        return Ok(Outcome::Failed(translate_lines(&compiled.stderr, entries, wrapped)));
+    }
+    if lang.no_run {
+        return Ok(Outcome::Passed);
+    }
+
+    let ran = Command::new(&exe_path).output()?;
+    if lang.should_panic {
+        return Ok(if ran.status.success() {
+            Outcome::Failed("expected should_panic, but it exited successfully".to_string())
+        } else {
+            Outcome::Passed
+        });
+    }
+    Ok(if ran.status.success() {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(translate_lines(&ran.stderr, entries, wrapped))
+    })
+}
+
+/// Wrap a block in a `fn main` unless it already declares one, the same
+/// convention rustdoc's doctest runner uses.
+fn wrap(code: &str, wrapped: bool) -> String {
+    if wrapped {
+        format!("fn main() {{\n{}\n}}\n", code)
+    } else {
+        code.to_string()
+    }
+}
+
+/// Rewrite every `main.rs:N` line reference in a captured diagnostic so
+/// `N` names the originating Markdown line instead of the throwaway temp
+/// file, mapping through `entries` — the same splice-by-splice
+/// provenance `--sourcemap` emits — rather than a single linear offset,
+/// since a block that references other sections has more than one
+/// origin. `wrapped` accounts for the `fn main() {` line injected by
+/// `wrap` shifting every generated line down by one.
+fn translate_lines(bytes: &[u8], entries: &[SourceMapEntry], wrapped: bool) -> String {
+    let text = String::from_utf8_lossy(bytes);
+    let header_lines = if wrapped { 1 } else { 0 };
+
+    MAIN_RS_LINE
+        .replace_all(&text, |caps: &::regex::Captures| {
+            let temp_line: usize = caps[1].parse().unwrap_or(1);
+            let gen_line = temp_line.saturating_sub(header_lines).max(1);
+            format!("main.rs:{}", map_gen_line(entries, gen_line))
+        })
+        .into_owned()
+}
+
+/// The Markdown line a generated line maps to: the `src_line` of the
+/// latest provenance entry at or before `gen_line`, offset by however
+/// far past that splice point `gen_line` falls.
+fn map_gen_line(entries: &[SourceMapEntry], gen_line: usize) -> usize {
+    let mut mapped = gen_line;
+    for entry in entries {
+        if entry.gen_line > gen_line {
+            break;
+        }
+        mapped = entry.src_line + (gen_line - entry.gen_line);
+    }
+    mapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{map_gen_line, translate_lines, FN_MAIN};
+    use tangle::SourceMapEntry;
+
+    fn entry(gen_line: usize, src_line: usize) -> SourceMapEntry {
+        SourceMapEntry {
+            gen_line,
+            src_path: "<test>".to_string(),
+            src_line,
+        }
+    }
+
+    #[test]
+    fn fn_main_matches_a_real_main_but_not_a_lookalike() {
+        assert!(FN_MAIN.is_match("fn main() {\n}"));
+        assert!(FN_MAIN.is_match("fn main () { }"));
+        assert!(!FN_MAIN.is_match("fn main_helper() {\n}"));
+    }
+
+    #[test]
+    fn translates_wrapped_block() {
+        // Block starts on Markdown line 10; the wrapper's `fn main() {`
+        // becomes temp line 1, so temp line 2 is the block's own line 1.
+        let entries = vec![entry(1, 10)];
+        let out = translate_lines(b"error: oops\n --> main.rs:2:5", &entries, true);
+        assert_eq!(out, "error: oops\n --> main.rs:10:5");
+    }
+
+    #[test]
+    fn translates_unwrapped_block() {
+        let entries = vec![entry(1, 4)];
+        let out = translate_lines(b"--> main.rs:1:1", &entries, false);
+        assert_eq!(out, "--> main.rs:4:1");
+    }
+
+    #[test]
+    fn maps_through_a_spliced_reference() {
+        // gen_line 1 is the block's own first line (Markdown line 10);
+        // gen_line 2 is where a `<<body>>` reference was spliced in,
+        // pulling in lines starting at that section's own line 20.
+        let entries = vec![entry(1, 10), entry(2, 20)];
+        assert_eq!(map_gen_line(&entries, 1), 10);
+        assert_eq!(map_gen_line(&entries, 2), 20);
+        assert_eq!(map_gen_line(&entries, 3), 21);
+    }
+}
@@ -0,0 +1,192 @@
+//! Render a literate Markdown document to a standalone HTML page, with
+//! code blocks tagged by language and cross-linked to the sections they
+//! reference. No syntax highlighting is done here; each block only
+//! carries a `language-*` class for a client-side highlighter. Mirrors rustdoc's standalone-Markdown rendering, including
+//! its `--markdown-css`/`--markdown-in-header`/`--markdown-before-content`/
+//! `--markdown-after-content` splice points.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::Read;
+use std::path::PathBuf;
+
+use failure::Error;
+use pulldown_cmark::{html, Event, Parser, Tag};
+
+use block_parse;
+use code_extractor;
+use tangle::{self, Tangle};
+use Ilk;
+
+/// Files to splice into the generated HTML, one `Vec` per rustdoc-style
+/// flag, emitted in the order given on the command line.
+#[derive(Debug, Default)]
+pub struct WeaveOptions {
+    pub css: Vec<PathBuf>,
+    pub in_header: Vec<PathBuf>,
+    pub before_content: Vec<PathBuf>,
+    pub after_content: Vec<PathBuf>,
+}
+
+/// Render `markdown` to a standalone HTML document.
+pub fn weave(markdown: &str, opts: &WeaveOptions) -> Result<String, Error> {
+    let (title, markdown) = strip_leading_title(markdown);
+    let tangle = Tangle::new(markdown)?;
+    let sections: HashSet<&str> = tangle.section_names().into_iter().collect();
+
+    let mut body = String::new();
+    html::push_html(&mut body, linkify(markdown, &sections));
+
+    let mut out = String::new();
+    out.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+    if let Some(title) = title {
+        out.push_str(&format!("<title>{}</title>\n", escape(title)));
+    }
+    for css in &opts.css {
+        out.push_str(&format!(
+            "<link rel=\"stylesheet\" href=\"{}\">\n",
+            escape_attr(&css.display().to_string())
+        ));
+    }
+    for file in &opts.in_header {
+        out.push_str(&slurp(file)?);
+        out.push('\n');
+    }
+    out.push_str("</head>\n<body>\n");
+    for file in &opts.before_content {
+        out.push_str(&slurp(file)?);
+        out.push('\n');
+    }
+    out.push_str(&body);
+    for file in &opts.after_content {
+        out.push_str(&slurp(file)?);
+        out.push('\n');
+    }
+    out.push_str("</body>\n</html>\n");
+
+    Ok(out)
+}
+
+/// Pull a leading `% Title` line off the document, Pandoc-style, and
+/// return the title alongside the remaining Markdown with that line (and
+/// its trailing newline) removed, so it isn't also rendered as a `<p>`.
+fn strip_leading_title(markdown: &str) -> (Option<&str>, &str) {
+    let first = match markdown.lines().next() {
+        Some(first) => first,
+        None => return (None, markdown),
+    };
+    if !first.starts_with('%') {
+        return (None, markdown);
+    }
+
+    let title = first[1..].trim();
+    let rest = &markdown[first.len()..];
+    (Some(title), rest.trim_start_matches('\n'))
+}
+
+/// Wrap each fenced code block in a `<pre><code>` tagged with its
+/// language, turn any `<<name>>` reference inside it that names a known
+/// section into a link to that section's anchor, and — if the block
+/// itself is a `<<name>>=` section definition — give it that anchor via
+/// `id="section-name"` so the link has somewhere to land.
+///
+/// This only emits a `class="language-*"` hook; there's no bundled
+/// tokenizer, so actual highlighting is left to a client-side
+/// highlighter (e.g. highlight.js) supplied via `--markdown-in-header`,
+/// the same split rustdoc itself uses for `--html-in-header`.
+///
+/// A block's text can arrive as more than one `Text` event, and the
+/// defining header (if any) is only knowable once the block's full body
+/// is in hand, so the whole block is buffered and rendered as a single
+/// `Html` event on `End` rather than streamed event-by-event.
+fn linkify<'a>(markdown: &'a str, sections: &'a HashSet<&'a str>) -> impl Iterator<Item = Event<'a>> {
+    let mut in_code = false;
+    let mut info = String::new();
+    let mut body = String::new();
+    Parser::new(markdown).map(move |event| match event {
+        Event::Start(Tag::CodeBlock(ref fence_info)) => {
+            in_code = true;
+            info = fence_info.to_string();
+            body.clear();
+            Event::Html("".into())
+        }
+        Event::Text(text) => if in_code {
+            body.push_str(&text);
+            Event::Html("".into())
+        } else {
+            Event::Text(text)
+        },
+        Event::End(Tag::CodeBlock(_)) => {
+            in_code = false;
+            // Only the bare language token (e.g. "rust" out of
+            // "rust,no_run") is a class a client-side highlighter would
+            // key off; a malformed info string just yields no class.
+            let lang = code_extractor::parse_lang_string(&info)
+                .map(|parsed| parsed.lang)
+                .unwrap_or_default();
+            // Narrow past the `<<name>>=` header (if any) before looking
+            // for references to link, so a section's own definition line
+            // doesn't get rewritten into a link to itself.
+            let (name, code_lo, _) = tangle::split_header(&body, 0, body.len());
+            let id_attr = if !name.is_empty() && sections.contains(name) {
+                format!(" id=\"section-{}\"", escape_attr(name))
+            } else {
+                String::new()
+            };
+            Event::Html(
+                format!(
+                    "<pre><code class=\"language-{}\"{}>{}{}</code></pre>\n",
+                    escape_attr(&lang),
+                    id_attr,
+                    escape(&body[..code_lo]),
+                    annotate_references(&body[code_lo..], sections)
+                ).into(),
+            )
+        }
+        other => other,
+    })
+}
+
+/// Replace every `<<name>>` in `text` that names a known section with an
+/// anchor link to `#section-name`; everything else passes through
+/// unchanged (and HTML-escaped, since it's about to be injected raw).
+fn annotate_references(text: &str, sections: &HashSet<&str>) -> String {
+    let mut out = String::new();
+    for span in block_parse::parse(text) {
+        let chunk = &text[span.lo..span.hi];
+        match span.ilk {
+            Ilk::SectionName => {
+                let name = block_parse::section_name(chunk);
+                if sections.contains(name) {
+                    out.push_str(&format!(
+                        "<a href=\"#section-{0}\">&lt;&lt;{1}&gt;&gt;</a>",
+                        escape_attr(name),
+                        escape(name)
+                    ));
+                } else {
+                    out.push_str(&escape(chunk));
+                }
+            }
+            _ => out.push_str(&escape(chunk)),
+        }
+    }
+    out
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Like `escape`, but also escapes `"` — for text dropped into an HTML
+/// attribute (`href`, `id`, ...) rather than element content.
+fn escape_attr(text: &str) -> String {
+    escape(text).replace('"', "&quot;")
+}
+
+fn slurp(path: &PathBuf) -> Result<String, Error> {
+    let mut result = String::new();
+    File::open(path)?.read_to_string(&mut result)?;
+    Ok(result)
+}